@@ -1,29 +1,62 @@
 use std::{io, process};
 
-use clap::Command;
+use clap::{Arg, ArgMatches, Command};
 use mdbook::{
     errors::Error,
     preprocess::{CmdPreprocessor, Preprocessor},
 };
+use semver::{Version, VersionReq};
 
 mod table;
 
 fn make_app() -> Command {
     Command::new("code-table")
         .about("A mdbook preprocessor that allows fenced code blocks in your markdown tables")
+        .subcommand(
+            Command::new("supports")
+                .arg(Arg::new("renderer").required(true))
+                .about("Check whether a renderer is supported by this preprocessor"),
+        )
 }
 
 fn main() {
-    make_app();
+    let matches = make_app().get_matches();
     let prep = table::CodeTables;
-    if let Err(e) = handle_processing(&prep) {
+    if let Some(sub_args) = matches.subcommand_matches("supports") {
+        handle_supports(&prep, sub_args);
+    } else if let Err(e) = handle_processing(&prep) {
         eprintln!("{}", e);
         process::exit(1);
     }
 }
 
+fn handle_supports(pre: &dyn Preprocessor, sub_args: &ArgMatches) -> ! {
+    let renderer = sub_args
+        .get_one::<String>("renderer")
+        .expect("Required argument");
+    // Signal support through the exit code, as mdbook expects.
+    if pre.supports_renderer(renderer) {
+        process::exit(0);
+    } else {
+        process::exit(1);
+    }
+}
+
 fn handle_processing(pre: &dyn Preprocessor) -> Result<(), Error> {
     let (ctx, book) = CmdPreprocessor::parse_input(io::stdin())?;
+    // Warn on a major/minor incompatible mdbook, as other preprocessors do,
+    // rather than on every patch-level difference.
+    if let Ok(book_version) = Version::parse(&ctx.mdbook_version) {
+        let version_req = VersionReq::parse(mdbook::MDBOOK_VERSION)?;
+        if !version_req.matches(&book_version) {
+            eprintln!(
+                "Warning: The code-table preprocessor was built against mdbook version {}, \
+                 but we're being called from version {}. Output may be incorrect.",
+                mdbook::MDBOOK_VERSION,
+                ctx.mdbook_version
+            );
+        }
+    }
     let processed = pre.run(&ctx, book)?;
     serde_json::to_writer(io::stdout(), &processed)?;
     Ok(())
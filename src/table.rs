@@ -1,104 +1,496 @@
-use std::collections::VecDeque;
+use std::collections::HashMap;
+use std::mem;
 
 use mdbook::{
     book::{Book, Chapter},
     preprocess::{Preprocessor, PreprocessorContext},
     BookItem,
 };
+use pulldown_cmark::{html, Alignment, Event, Options, Parser, Tag};
 
 #[derive(PartialEq, Eq)]
 enum RowType {
-    Headings,
-    Alignments,
-    TextEntry,
-    CodeEntry,
-    Empty,
+    Heading,
+    Data,
 }
-#[derive(Default)]
+
+/// A single rendered table cell. Code spans are kept apart from prose so they
+/// can be wrapped in a highlight.js-compatible `<pre><code>` element, while
+/// everything else is inline markup already rendered (and escaped) by
+/// `pulldown-cmark`.
+enum Cell {
+    Html(String),
+    Code { lang: Option<String>, body: String },
+}
+
 struct TableRow {
-    contents: Vec<String>,
-    row_types: Vec<RowType>,
+    cells: Vec<Cell>,
+    row_type: RowType,
+}
+
+/// Escape the characters that are significant inside HTML text, mirroring the
+/// `Escape` helper rustdoc runs over code spans before emitting them.
+fn escape_html(input: &str) -> String {
+    let mut escaped = String::with_capacity(input.len());
+    for c in input.chars() {
+        match c {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '&' => escaped.push_str("&amp;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
 }
 
 #[derive(Default)]
 struct CodeTable {
     rows: Vec<TableRow>,
+    /// Per-column alignment as reported by the delimiter row. Columns beyond
+    /// this vector (or marked `Alignment::None`) fall back to the default.
+    alignments: Vec<Alignment>,
 }
 
 pub struct CodeTables;
 
 impl CodeTables {
     const CODE_ANNOTATION: &'static str = "@code";
-    const MAX_LOOP_STEPS: u32 = 2048;
+    /// Private-use character used to fence off a lifted code block in the
+    /// sanitized table so it survives the markdown parse as opaque text.
+    const FENCE_SENTINEL: char = '\u{E000}';
+    const CODE_DEF_START: &'static str = "@code-def";
+    const CODE_DEF_END: &'static str = "@code-end";
+    const CODE_DEF_REF: &'static str = "@ref";
+    /// Upper bound on snippet expansion passes, guarding against recursive or
+    /// self-referential templates.
+    const MAX_EXPANSION_DEPTH: u32 = 16;
 
-    fn get_table_row(string: &str, is_first: bool) -> TableRow {
-        let entries: Vec<String> = string
-            .split('|')
-            .map(|dirty| dirty.trim().to_string())
-            .collect();
-        let mut types: Vec<RowType> = Vec::new();
-        if is_first {
-            types.resize_with(entries.len(), || RowType::Headings);
-        } else {
-            for e in entries.clone() {
-                if e.contains('-') && !e.contains(' ') {
-                    types.push(RowType::Alignments);
-                    continue;
+    /// Parse the markdown table that immediately follows a `@code` marker.
+    ///
+    /// The region is first run through [`Self::scan_table`], which is aware of
+    /// triple-backtick fences: a cell may open a fenced block that spans
+    /// several source lines, and those lines (pipes and blanks included) are
+    /// lifted out into a sentinel before the table is handed to
+    /// `pulldown-cmark`. The parser then drives cell boundaries off the real
+    /// table grammar and renders inline markup, and the lifted fences are
+    /// restored as multi-line code cells. The returned offset is the byte
+    /// length consumed within `string`, letting the caller resume after it.
+    fn get_table_metadata(string: &str) -> Option<(CodeTable, usize)> {
+        let (sanitized, consumed, fences) = Self::scan_table(string);
+
+        let mut options = Options::empty();
+        options.insert(Options::ENABLE_TABLES);
+
+        let mut table: CodeTable = Default::default();
+        let mut found = false;
+
+        let mut cells: Vec<Cell> = Vec::new();
+        let mut cell_events: Vec<Event> = Vec::new();
+        let mut in_cell = false;
+
+        for event in Parser::new_ext(&sanitized, options) {
+            match event {
+                Event::Start(Tag::Table(aligns)) => {
+                    found = true;
+                    table.alignments = aligns;
+                }
+                Event::End(Tag::Table(_)) => break,
+                Event::Start(Tag::TableHead) => cells.clear(),
+                Event::End(Tag::TableHead) => table.rows.push(TableRow {
+                    cells: mem::take(&mut cells),
+                    row_type: RowType::Heading,
+                }),
+                Event::Start(Tag::TableRow) => cells.clear(),
+                Event::End(Tag::TableRow) => table.rows.push(TableRow {
+                    cells: mem::take(&mut cells),
+                    row_type: RowType::Data,
+                }),
+                Event::Start(Tag::TableCell) => {
+                    in_cell = true;
+                    cell_events.clear();
+                }
+                Event::End(Tag::TableCell) => {
+                    cells.push(Self::build_cell(mem::take(&mut cell_events), &fences));
+                    in_cell = false;
+                }
+                other if in_cell => cell_events.push(other),
+                _ => {}
+            }
+        }
+
+        if !found {
+            return None;
+        }
+        Some((table, consumed))
+    }
+
+    /// Scan the `@code` region line by line, tracking fence state so that a
+    /// triple-backtick block opened inside a cell continues across later lines
+    /// (pipes and blank lines included) until its closing fence, instead of
+    /// the table terminating at the first line without a `|`.
+    ///
+    /// Returns a sanitized table string in which every multi-line fence has
+    /// been collapsed onto its row as a [`Self::FENCE_SENTINEL`]-wrapped index,
+    /// the byte length consumed within `string`, and the `(language, body)` of
+    /// each lifted fence keyed by that index.
+    fn scan_table(string: &str) -> (String, usize, Vec<(Option<String>, String)>) {
+        let mut sanitized = String::new();
+        let mut consumed: usize = 0;
+        let mut fences: Vec<(Option<String>, String)> = Vec::new();
+
+        let mut started = false;
+        let mut in_fence = false;
+        let mut fence_lang: Option<String> = None;
+        let mut fence_body = String::new();
+        let mut pending_prefix = String::new();
+        // Snapshot taken when a multi-line fence opens, so an unclosed fence
+        // can be rolled back at EOF rather than swallowing the chapter tail.
+        let mut fence_checkpoint_consumed: usize = 0;
+        let mut fence_checkpoint_len: usize = 0;
+
+        for line in string.split_inclusive('\n') {
+            let bare = line.trim_end_matches(['\n', '\r']);
+
+            if in_fence {
+                consumed += line.len();
+                if let Some(at) = bare.find("```") {
+                    let (body_tail, rest) = bare.split_at(at);
+                    fence_body.push_str(body_tail);
+                    let remainder = &rest["```".len()..];
+                    let index = fences.len();
+                    fences.push((fence_lang.take(), mem::take(&mut fence_body)));
+                    sanitized.push_str(&pending_prefix);
+                    sanitized.push(Self::FENCE_SENTINEL);
+                    sanitized.push_str(&index.to_string());
+                    sanitized.push(Self::FENCE_SENTINEL);
+                    sanitized.push_str(remainder);
+                    sanitized.push('\n');
+                    pending_prefix.clear();
+                    in_fence = false;
+                } else {
+                    fence_body.push_str(line);
                 }
-                if e.contains('`') && e.split('`').count() > 1 {
-                    types.push(RowType::CodeEntry);
+                continue;
+            }
+
+            if let Some(at) = bare.find("```") {
+                let (prefix, rest) = bare.split_at(at);
+                let after_open = &rest["```".len()..];
+
+                // A closing fence on the same line is a complete single-line
+                // code cell; emit it without entering multi-line fence state.
+                // A lone opener such as `| ``` |` is not handled here: it
+                // takes the multi-line path below, and is rolled back at EOF
+                // if no later line closes it.
+                if let Some(close_at) = after_open.find("```") {
+                    let (body, tail) = after_open.split_at(close_at);
+                    let remainder = &tail["```".len()..];
+                    let index = fences.len();
+                    fences.push((None, body.to_string()));
+                    consumed += line.len();
+                    started = true;
+                    sanitized.push_str(prefix);
+                    sanitized.push(Self::FENCE_SENTINEL);
+                    sanitized.push_str(&index.to_string());
+                    sanitized.push(Self::FENCE_SENTINEL);
+                    sanitized.push_str(remainder);
+                    sanitized.push('\n');
                     continue;
                 }
-                if e.is_empty() {
-                    types.push(RowType::Empty);
+
+                let lang = after_open.split_whitespace().next();
+                fence_checkpoint_consumed = consumed;
+                fence_checkpoint_len = sanitized.len();
+                consumed += line.len();
+                started = true;
+                in_fence = true;
+                fence_lang = lang.map(str::to_string);
+                fence_body.clear();
+                pending_prefix = prefix.to_string();
+                continue;
+            }
+
+            if !bare.contains('|') {
+                if started {
+                    // first line outside the table grammar ends it
+                    break;
+                }
+                if bare.trim().is_empty() {
+                    // leading whitespace between `@code` and the table
+                    consumed += line.len();
                     continue;
                 }
-                types.push(RowType::TextEntry);
+                break;
+            }
+
+            started = true;
+            consumed += line.len();
+            sanitized.push_str(bare);
+            sanitized.push('\n');
+        }
+
+        if in_fence {
+            // The fence never closed: roll back to the opener so the malformed
+            // region is left as literal chapter text instead of consumed.
+            sanitized.truncate(fence_checkpoint_len);
+            consumed = fence_checkpoint_consumed;
+        }
+
+        (sanitized, consumed, fences)
+    }
+
+    /// Turn the inline events of one cell into a [`Cell`]. A cell that is a
+    /// lifted fence (a lone [`Self::FENCE_SENTINEL`] sentinel) or a single
+    /// inline code span becomes a code cell; anything else is rendered as
+    /// inline HTML, which escapes its text nodes for us.
+    fn build_cell(events: Vec<Event>, fences: &[(Option<String>, String)]) -> Cell {
+        // pulldown-cmark pads cells with empty text events around inline
+        // content, so ignore whitespace-only text when deciding whether the
+        // cell is a single code span or lifted fence.
+        let meaningful: Vec<&Event> = events
+            .iter()
+            .filter(|event| !matches!(event, Event::Text(text) if text.trim().is_empty()))
+            .collect();
+
+        if let [Event::Text(text)] = meaningful.as_slice() {
+            if let Some((lang, body)) = Self::resolve_fence(text, fences) {
+                return Cell::Code {
+                    lang: lang.clone(),
+                    body: body.clone(),
+                };
+            }
+        }
+        if let [Event::Code(code)] = meaningful.as_slice() {
+            return Cell::Code {
+                lang: None,
+                body: code.to_string(),
+            };
+        }
+        let mut rendered = String::new();
+        html::push_html(&mut rendered, events.into_iter());
+        Cell::Html(rendered.trim().to_string())
+    }
+
+    /// Resolve a sentinel cell emitted by [`Self::scan_table`] back to the
+    /// `(language, body)` of its lifted fence, if the text is one.
+    fn resolve_fence<'a>(
+        text: &str,
+        fences: &'a [(Option<String>, String)],
+    ) -> Option<&'a (Option<String>, String)> {
+        let inner = text
+            .trim()
+            .strip_prefix(Self::FENCE_SENTINEL)?
+            .strip_suffix(Self::FENCE_SENTINEL)?;
+        let index: usize = inner.parse().ok()?;
+        fences.get(index)
+    }
+
+    /// Collect every `@code-def <name>` … `@code-end` block into a snippet map
+    /// and return the chapter text with those definition blocks removed, so
+    /// they are never rendered themselves.
+    fn collect_snippets(content: &str) -> (HashMap<String, String>, String) {
+        let mut snippets: HashMap<String, String> = HashMap::new();
+        let mut remaining = String::with_capacity(content.len());
+
+        let mut name = String::new();
+        let mut body = String::new();
+        let mut in_def = false;
+
+        for line in content.lines() {
+            let trimmed = line.trim();
+            if in_def {
+                if trimmed == Self::CODE_DEF_END {
+                    snippets.insert(mem::take(&mut name), mem::take(&mut body));
+                    in_def = false;
+                } else {
+                    if !body.is_empty() {
+                        body.push('\n');
+                    }
+                    body.push_str(line);
+                }
+                continue;
+            }
+            if let Some(rest) = trimmed.strip_prefix(Self::CODE_DEF_START) {
+                name = rest.trim().to_string();
+                body.clear();
+                in_def = true;
+                continue;
             }
+            remaining.push_str(line);
+            remaining.push('\n');
         }
 
-        TableRow {
-            contents: entries,
-            row_types: types,
+        if in_def {
+            // An unterminated `@code-def` is never a valid snippet; leave the
+            // partially-read block in the text rather than dropping it.
+            remaining.push_str(Self::CODE_DEF_START);
+            if !name.is_empty() {
+                remaining.push(' ');
+                remaining.push_str(&name);
+            }
+            remaining.push('\n');
+            if !body.is_empty() {
+                remaining.push_str(&body);
+                remaining.push('\n');
+            }
         }
+
+        (snippets, remaining)
     }
 
-    fn get_table_metadata(string: &str) -> Option<(CodeTable, usize)> {
-        let mut section_size: usize = 0;
-        let mut table_lines: VecDeque<&str> = VecDeque::new();
-        for line in string.lines() {
-            if !line.contains('|') {
-                // first line without | signifies end of table
+    /// Repeatedly expand `{{name …}}` / `@ref name` references until the text
+    /// reaches a fixed point or [`Self::MAX_EXPANSION_DEPTH`] passes elapse,
+    /// the latter bounding recursive or self-referential snippets.
+    fn expand_snippets(content: &str, snippets: &HashMap<String, String>) -> String {
+        let mut current = content.to_string();
+        for _ in 0..Self::MAX_EXPANSION_DEPTH {
+            let expanded = Self::expand_once(&current, snippets);
+            if expanded == current {
                 break;
             }
-            table_lines.push_back(line);
+            current = expanded;
         }
-        if table_lines.is_empty() {
-            return None;
+        current
+    }
+
+    /// Perform a single expansion pass. `@ref name` is first normalized to the
+    /// `{{name}}` form, then each `{{…}}` reference is resolved against the
+    /// snippet map (unknown references are left untouched).
+    fn expand_once(content: &str, snippets: &HashMap<String, String>) -> String {
+        let normalized = Self::rewrite_refs(content);
+        let mut out = String::with_capacity(normalized.len());
+        let mut rest = normalized.as_str();
+
+        while let Some(start) = rest.find("{{") {
+            out.push_str(&rest[..start]);
+            let after = &rest[start + 2..];
+            let Some(end) = after.find("}}") else {
+                out.push_str(&rest[start..]);
+                return out;
+            };
+            let inner = after[..end].trim();
+            match Self::render_reference(inner, snippets) {
+                Some(rendered) => out.push_str(&rendered),
+                None => {
+                    out.push_str("{{");
+                    out.push_str(inner);
+                    out.push_str("}}");
+                }
+            }
+            rest = &after[end + 2..];
         }
-        let mut table_buffer: CodeTable = Default::default();
-        let first = table_lines.pop_front().unwrap();
-        section_size += first.len();
-        table_buffer.rows.push(Self::get_table_row(first, true));
+        out.push_str(rest);
+        out
+    }
 
-        for line in table_lines {
-            section_size += line.len();
-            table_buffer.rows.push(Self::get_table_row(line, false));
+    /// Rewrite the bare `@ref name` token form into the `{{name}}` form so a
+    /// single resolver handles both spellings.
+    fn rewrite_refs(content: &str) -> String {
+        let marker = format!("{} ", Self::CODE_DEF_REF);
+        let mut out = String::with_capacity(content.len());
+        let mut rest = content;
+        while let Some(at) = rest.find(&marker) {
+            out.push_str(&rest[..at]);
+            let after = &rest[at + marker.len()..];
+            let name_end = after.find(char::is_whitespace).unwrap_or(after.len());
+            out.push_str("{{");
+            out.push_str(&after[..name_end]);
+            out.push_str("}}");
+            rest = &after[name_end..];
         }
-        section_size += string.split_at(section_size).1.find('\n').unwrap_or(0); // append to next line break if one exists
-        Some((table_buffer, section_size))
+        out.push_str(rest);
+        out
     }
 
-    fn parse_chapter_contents(chapter: &Chapter) -> Chapter {
-        let mut content = String::with_capacity(chapter.content.len());
-        let mut buffer = chapter.content.clone();
+    /// Resolve a single reference body (`name arg1 key=value …`) to the stored
+    /// snippet with its variables substituted, or `None` if the name is unknown.
+    fn render_reference(inner: &str, snippets: &HashMap<String, String>) -> Option<String> {
+        let mut tokens = inner.split_whitespace();
+        let name = tokens.next()?;
+        let body = snippets.get(name)?;
 
-        // safer while loop. Guaranteed exit point
-        for _ in 0..Self::MAX_LOOP_STEPS {
-            if buffer.is_empty() {
-                // while cond
-                break;
+        let mut positional: Vec<&str> = Vec::new();
+        let mut named: HashMap<&str, &str> = HashMap::new();
+        for token in tokens {
+            if let Some((key, value)) = token.split_once('=') {
+                named.insert(key, value);
+            } else {
+                positional.push(token);
+            }
+        }
+
+        Some(Self::substitute_vars(body, &positional, &named))
+    }
+
+    /// Substitute `$N` (positional, 1-based) and `$name` (named) placeholders
+    /// in `body`. Placeholders are tokenized — the identifier or digit run
+    /// after `$` is matched whole — so `$1` never collides with `$10` and
+    /// `$name` never collides with `$namespace`. Unknown placeholders are left
+    /// verbatim.
+    fn substitute_vars(
+        body: &str,
+        positional: &[&str],
+        named: &HashMap<&str, &str>,
+    ) -> String {
+        let mut out = String::with_capacity(body.len());
+        let mut rest = body;
+        while let Some(at) = rest.find('$') {
+            out.push_str(&rest[..at]);
+            let after = &rest[at + 1..];
+
+            let digits = after
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(after.len());
+            if digits > 0 {
+                let value = after[..digits]
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|n| positional.get(n.wrapping_sub(1)));
+                match value {
+                    Some(value) => out.push_str(value),
+                    None => {
+                        out.push('$');
+                        out.push_str(&after[..digits]);
+                    }
+                }
+                rest = &after[digits..];
+                continue;
             }
+
+            let ident = after
+                .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .unwrap_or(after.len());
+            if ident > 0 {
+                match named.get(&after[..ident]) {
+                    Some(value) => out.push_str(value),
+                    None => {
+                        out.push('$');
+                        out.push_str(&after[..ident]);
+                    }
+                }
+                rest = &after[ident..];
+                continue;
+            }
+
+            // A lone `$` with no placeholder name.
+            out.push('$');
+            rest = after;
+        }
+        out.push_str(rest);
+        out
+    }
+
+    fn parse_chapter_contents(chapter: &Chapter) -> Chapter {
+        let (snippets, stripped) = Self::collect_snippets(&chapter.content);
+        let expanded = Self::expand_snippets(&stripped, &snippets);
+
+        let mut content = String::with_capacity(expanded.len());
+        let mut buffer = expanded;
+
+        // Each iteration removes at least the `@code` marker (plus the table
+        // the parser reports consuming), so `buffer` strictly shrinks.
+        while !buffer.is_empty() {
             let target = buffer.find(Self::CODE_ANNOTATION);
             let Some(index) = target else {
                 content.push_str(buffer.as_str());
@@ -147,24 +539,25 @@ impl Preprocessor for CodeTables {
         }
         Ok(parsed_book)
     }
+
+    fn supports_renderer(&self, renderer: &str) -> bool {
+        renderer == "html"
+    }
 }
 
 impl CodeTable {
     fn get_as_html(&self) -> String {
-        let filter_data = |val: &&TableRow| -> bool { !val.row_types.contains(&RowType::Headings) };
-        let filter_headings =
-            |val: &&TableRow| -> bool { val.row_types.contains(&RowType::Headings) };
-        let data_rows: String = self
+        let heading: String = self
             .rows
             .iter()
-            .filter(filter_data)
-            .map(|row| row.get_as_html())
+            .filter(|row| row.row_type == RowType::Heading)
+            .map(|row| row.get_as_html(&self.alignments))
             .collect();
-        let heading: String = self
+        let data_rows: String = self
             .rows
             .iter()
-            .filter(filter_headings)
-            .map(|row| row.get_as_html())
+            .filter(|row| row.row_type == RowType::Data)
+            .map(|row| row.get_as_html(&self.alignments))
             .collect();
         format!(
             r"<table>
@@ -178,19 +571,239 @@ impl CodeTable {
     }
 }
 
-impl TableRow {
+impl Cell {
+    /// Render the cell's inner HTML (without the surrounding `<th>`/`<td>`).
     fn get_as_html(&self) -> String {
+        match self {
+            Cell::Html(html) => html.clone(),
+            Cell::Code { lang, body } => {
+                let class = match lang {
+                    Some(lang) => format!(" class=\"language-{}\"", escape_html(lang)),
+                    None => String::new(),
+                };
+                format!("<pre><code{}>{}</code></pre>", class, escape_html(body))
+            }
+        }
+    }
+}
+
+impl TableRow {
+    fn get_as_html(&self, alignments: &[Alignment]) -> String {
         let mut entries_data = String::new();
-        for (index, entry) in self.contents.iter().enumerate() {
-            let data: String = match self.row_types[index] {
-                RowType::Alignments => "".to_string(),
-                RowType::Empty => "".to_string(),
-                RowType::Headings => format!("<th>{}</th>", entry),
-                RowType::CodeEntry => format!("<td><pre>{}</pre><td>", entry),
-                RowType::TextEntry => format!("<td>{}</td>", entry),
+        for (index, cell) in self.cells.iter().enumerate() {
+            let style = alignments
+                .get(index)
+                .map(|a| Self::alignment_style(*a))
+                .unwrap_or("");
+            let inner = cell.get_as_html();
+            let data = match self.row_type {
+                RowType::Heading => format!("<th{}>{}</th>", style, inner),
+                RowType::Data => format!("<td{}>{}</td>", style, inner),
             };
             entries_data += data.as_str();
         }
         format!(r"<tr>{}</tr>", entries_data)
     }
+
+    /// The inline `style` attribute (including the leading space) for a column
+    /// alignment, or an empty string for the default alignment.
+    fn alignment_style(align: Alignment) -> &'static str {
+        match align {
+            Alignment::Left => " style=\"text-align:left\"",
+            Alignment::Right => " style=\"text-align:right\"",
+            Alignment::Center => " style=\"text-align:center\"",
+            Alignment::None => "",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_html_text() {
+        assert_eq!(escape_html("a<b>&\"c"), "a&lt;b&gt;&amp;&quot;c");
+    }
+
+    #[test]
+    fn code_cell_is_well_formed_and_escaped() {
+        let cell = Cell::Code {
+            lang: Some("rust".to_string()),
+            body: "let x = a < b && c;".to_string(),
+        };
+        assert_eq!(
+            cell.get_as_html(),
+            "<pre><code class=\"language-rust\">let x = a &lt; b &amp;&amp; c;</code></pre>"
+        );
+    }
+
+    #[test]
+    fn code_cell_without_language_omits_class() {
+        let cell = Cell::Code {
+            lang: None,
+            body: "plain".to_string(),
+        };
+        assert_eq!(cell.get_as_html(), "<pre><code>plain</code></pre>");
+    }
+
+    #[test]
+    fn code_cell_language_is_escaped() {
+        let cell = Cell::Code {
+            lang: Some("x\"onmouseover".to_string()),
+            body: "z".to_string(),
+        };
+        assert!(cell
+            .get_as_html()
+            .contains("class=\"language-x&quot;onmouseover\""));
+    }
+
+    #[test]
+    fn angle_brackets_in_a_code_cell_are_escaped() {
+        let src = "\n| head |\n| --- |\n| `<script>` |\n";
+        let (table, _) = CodeTables::get_table_metadata(src).expect("a table");
+        let html = table.get_as_html();
+        assert!(html.contains("<pre><code>&lt;script&gt;</code></pre>"));
+        assert!(!html.contains("<script>"));
+    }
+
+    #[test]
+    fn multi_line_fence_is_lifted_and_rendered() {
+        let src = "\n| name | code |\n| --- | --- |\n| demo | ```rust\nfn main() {}\nlet x = 1;\n``` |\nafter\n";
+        let (sanitized, consumed, fences) = CodeTables::scan_table(src);
+
+        assert_eq!(fences.len(), 1);
+        assert_eq!(fences[0].0.as_deref(), Some("rust"));
+        assert_eq!(fences[0].1, "fn main() {}\nlet x = 1;\n");
+        assert!(sanitized.contains(CodeTables::FENCE_SENTINEL));
+        // the first line outside the table grammar is left untouched
+        assert_eq!(&src[consumed..], "after\n");
+
+        let (table, _) = CodeTables::get_table_metadata(src).expect("a table");
+        let html = table.get_as_html();
+        assert!(html.contains(
+            "<pre><code class=\"language-rust\">fn main() {}\nlet x = 1;\n</code></pre>"
+        ));
+    }
+
+    #[test]
+    fn single_line_fence_closes_without_entering_multiline() {
+        let src = "| x | ```print``` |\n| --- | --- |\n";
+        let (_, _, fences) = CodeTables::scan_table(src);
+        assert_eq!(fences.len(), 1);
+        assert_eq!(fences[0].0, None);
+        assert_eq!(fences[0].1, "print");
+    }
+
+    #[test]
+    fn unclosed_fence_is_rolled_back() {
+        let src = "| a | b |\n| --- | --- |\n| x | ```rust\nnever closes\n";
+        let (sanitized, consumed, fences) = CodeTables::scan_table(src);
+        assert!(fences.is_empty());
+        assert!(!sanitized.contains(CodeTables::FENCE_SENTINEL));
+        // everything from the opener line onward stays as literal text
+        assert_eq!(&src[consumed..], "| x | ```rust\nnever closes\n");
+    }
+
+    #[test]
+    fn degenerate_lone_fence_is_not_a_table() {
+        let src = "| ``` |\n| --- |\n";
+        assert!(CodeTables::get_table_metadata(src).is_none());
+    }
+
+    #[test]
+    fn alignment_row_drives_cell_styles() {
+        let src = "\n| h1 | h2 | h3 |\n| :--- | :---: | ---: |\n| a | b | c |\n";
+        let (table, _) = CodeTables::get_table_metadata(src).expect("a table");
+        let html = table.get_as_html();
+
+        assert!(html.contains("<th style=\"text-align:left\">h1</th>"));
+        assert!(html.contains("<th style=\"text-align:center\">h2</th>"));
+        assert!(html.contains("<th style=\"text-align:right\">h3</th>"));
+        assert!(html.contains("<td style=\"text-align:left\">a</td>"));
+
+        // the delimiter row is consumed by the parser, never emitted as data
+        assert!(!html.contains(":---"));
+        assert_eq!(
+            table
+                .rows
+                .iter()
+                .filter(|row| row.row_type == RowType::Data)
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn missing_alignment_columns_fall_back_to_default() {
+        let row = TableRow {
+            cells: vec![
+                Cell::Html("a".to_string()),
+                Cell::Html("b".to_string()),
+                Cell::Html("c".to_string()),
+            ],
+            row_type: RowType::Data,
+        };
+        // fewer alignments than cells: the trailing column defaults
+        let aligns = [Alignment::Left, Alignment::Right];
+        assert_eq!(
+            row.get_as_html(&aligns),
+            "<tr><td style=\"text-align:left\">a</td>\
+             <td style=\"text-align:right\">b</td>\
+             <td>c</td></tr>"
+        );
+    }
+
+    #[test]
+    fn positional_and_named_substitution() {
+        let mut named = HashMap::new();
+        named.insert("name", "X");
+        // `$1` must not swallow `$10`, and `$name` must not swallow `$namespace`
+        let out =
+            CodeTables::substitute_vars("$1 $10 $name $namespace", &["a", "b"], &named);
+        assert_eq!(out, "a $10 X $namespace");
+    }
+
+    #[test]
+    fn snippet_reference_expands_with_arguments() {
+        let content = "@code-def greet\nhello $1 and $name\n@code-end\n{{greet World name=Bob}}\n";
+        let (snippets, stripped) = CodeTables::collect_snippets(content);
+        assert!(snippets.contains_key("greet"));
+        // the definition block is stripped from the rendered text
+        assert!(!stripped.contains("@code-def"));
+
+        let expanded = CodeTables::expand_snippets(&stripped, &snippets);
+        assert!(expanded.contains("hello World and Bob"));
+    }
+
+    #[test]
+    fn ref_spelling_matches_brace_spelling() {
+        let content = "@code-def s\nbody\n@code-end\n@ref s\n";
+        let (snippets, stripped) = CodeTables::collect_snippets(content);
+        let expanded = CodeTables::expand_snippets(&stripped, &snippets);
+        assert!(expanded.contains("body"));
+    }
+
+    #[test]
+    fn recursive_snippet_is_bounded() {
+        let mut snippets = HashMap::new();
+        snippets.insert("loop".to_string(), "x {{loop}}".to_string());
+        // must terminate and stop after MAX_EXPANSION_DEPTH passes
+        let out = CodeTables::expand_snippets("{{loop}}", &snippets);
+        assert_eq!(
+            out.matches("x ").count(),
+            CodeTables::MAX_EXPANSION_DEPTH as usize
+        );
+        assert!(out.ends_with("{{loop}}"));
+    }
+
+    #[test]
+    fn unterminated_definition_is_left_intact() {
+        let content = "before\n@code-def oops\nline1\nline2\n";
+        let (snippets, stripped) = CodeTables::collect_snippets(content);
+        assert!(snippets.is_empty());
+        assert!(stripped.contains("@code-def oops"));
+        assert!(stripped.contains("line1"));
+        assert!(stripped.contains("line2"));
+    }
 }